@@ -9,77 +9,283 @@
 // except according to those terms.
 
 use euclid::{Point2D, Rect, Size2D};
-use gl::types::{GLenum, GLsizei, GLsizeiptr, GLuint, GLvoid};
+use gl::types::{GLenum, GLintptr, GLsizei, GLsizeiptr, GLuint, GLvoid};
 use gl;
 use outline::OutlineBuilder;
 use rect_packer::RectPacker;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::mem;
 use std::os::raw::c_void;
+use std::ptr;
 use std::u16;
 
 pub struct AtlasBuilder {
-    pub rect_packer: RectPacker,
-    image_descriptors: Vec<ImageDescriptor>,
-    image_metadata: Vec<ImageMetadata>,
+    available_width: u32,
+    shelf_height: u32,
+    pages: Vec<AtlasPageBuilder>,
+    /// Maps a `(glyph_id, point_size)` pair—where `point_size` is encoded the same way as in
+    /// `ImageDescriptor::point_size`—to the `(page_index, glyph_index)` of the already-packed
+    /// image, so the same glyph drawn at multiple sizes isn't packed twice.
+    glyph_cache: HashMap<(u16, u32), (u32, u32)>,
+    /// A monotonically increasing counter, advanced once per frame by `begin_frame`. Compared
+    /// against each `ImageMetadata::last_used` to decide what `evict_unused` can reclaim.
+    current_frame: u32,
+    /// How many frames a glyph may go untouched before `evict_unused` reclaims its space.
+    eviction_threshold: u32,
 }
 
 impl AtlasBuilder {
     /// FIXME(pcwalton): Including the shelf height here may be a bad API.
     #[inline]
-    pub fn new(available_width: u32, shelf_height: u32) -> AtlasBuilder {
+    pub fn new(available_width: u32, shelf_height: u32, eviction_threshold: u32) -> AtlasBuilder {
         AtlasBuilder {
-            rect_packer: RectPacker::new(available_width, shelf_height),
-            image_descriptors: vec![],
-            image_metadata: vec![],
+            available_width: available_width,
+            shelf_height: shelf_height,
+            pages: vec![AtlasPageBuilder::new(available_width, shelf_height)],
+            glyph_cache: HashMap::new(),
+            current_frame: 0,
+            eviction_threshold: eviction_threshold,
         }
     }
 
-    /// FIXME(pcwalton): Support the same glyph drawn at multiple point sizes.
+    /// Advances the frame counter used for LRU eviction. Call this once per frame before
+    /// packing or touching any glyphs.
+    #[inline]
+    pub fn begin_frame(&mut self) {
+        self.current_frame += 1;
+    }
+
+    /// Marks the glyph at `(page_index, glyph_index)`—as returned by `pack_glyph` or
+    /// `glyph_index_for`—as used in the current frame, protecting it from `evict_unused`.
+    #[inline]
+    pub fn touch(&mut self, page_index: u32, glyph_index: u32) {
+        let current_frame = self.current_frame;
+        self.pages[page_index as usize].touch(glyph_index, current_frame)
+    }
+
+    /// Reclaims the atlas space of every glyph that hasn't been touched (via `pack_glyph` or
+    /// `touch`) in the last `eviction_threshold` frames, returning their rectangles to each
+    /// page's `RectPacker` free list so future packs can reuse the space.
+    pub fn evict_unused(&mut self) {
+        let current_frame = self.current_frame;
+        let eviction_threshold = self.eviction_threshold;
+        let AtlasBuilder { ref mut pages, ref mut glyph_cache, .. } = *self;
+        for page in pages {
+            page.evict_unused(current_frame, eviction_threshold, glyph_cache);
+        }
+    }
+
+    /// Packs a glyph into the atlas, returning the `(page_index, glyph_index)` of the resulting
+    /// image.
+    ///
+    /// If this exact `(glyph_id, point_size)` pair has already been packed, the existing
+    /// location is returned directly and no new space is allocated. If the glyph doesn't fit on
+    /// the current page, stale glyphs are evicted (as by `evict_unused`) and the pack is retried
+    /// on that page; if it still doesn't fit, a fresh page is started automatically and the glyph
+    /// is packed there instead.
     pub fn pack_glyph(&mut self,
                       outline_builder: &OutlineBuilder,
-                      glyph_index: u32,
+                      outline_glyph_index: u32,
                       point_size: f32)
-                      -> Result<(), ()> {
+                      -> Result<(u32, u32), ()> {
+        let glyph_id = outline_builder.glyph_id(outline_glyph_index);
+        let fixed_point_size = (point_size * 65536.0) as u32;
+
+        if let Some(&location) = self.glyph_cache.get(&(glyph_id, fixed_point_size)) {
+            self.pages[location.0 as usize].touch(location.1, self.current_frame);
+            return Ok(location)
+        }
+
         // FIXME(pcwalton): I think this will check for negative values and panic, which is
         // unnecessary.
-        let pixel_size = outline_builder.glyph_pixel_bounds(glyph_index, point_size)
+        let pixel_size = outline_builder.glyph_pixel_bounds(outline_glyph_index, point_size)
                                         .size
                                         .ceil()
                                         .cast()
                                         .unwrap();
 
-        let glyph_id = outline_builder.glyph_id(glyph_index);
-
-        let atlas_origin = try!(self.rect_packer.pack(&pixel_size));
-
-        let glyph_index = self.image_descriptors.len() as u32;
+        let mut page_index = (self.pages.len() - 1) as u32;
+        let atlas_origin = match self.pages[page_index as usize].rect_packer.pack(&pixel_size) {
+            Ok(atlas_origin) => atlas_origin,
+            Err(()) => {
+                // Try to reclaim space from glyphs that have gone stale before paying for a
+                // whole new page.
+                self.evict_unused();
+                match self.pages[page_index as usize].rect_packer.pack(&pixel_size) {
+                    Ok(atlas_origin) => atlas_origin,
+                    Err(()) => {
+                        // Still doesn't fit: seal the current page up and start a fresh one.
+                        self.pages.push(AtlasPageBuilder::new(self.available_width,
+                                                               self.shelf_height));
+                        page_index = (self.pages.len() - 1) as u32;
+                        try!(self.pages[page_index as usize].rect_packer.pack(&pixel_size))
+                    }
+                }
+            }
+        };
 
-        while self.image_descriptors.len() < glyph_index as usize + 1 {
-            self.image_descriptors.push(ImageDescriptor::default())
-        }
+        let page = &mut self.pages[page_index as usize];
+        let glyph_index = page.alloc_slot();
 
-        self.image_descriptors[glyph_index as usize] = ImageDescriptor {
+        page.set_descriptor(glyph_index, ImageDescriptor {
             atlas_x: atlas_origin.x,
             atlas_y: atlas_origin.y,
-            point_size: (point_size * 65536.0) as u32,
+            point_size: fixed_point_size,
             glyph_index: glyph_index,
-        };
+        });
 
-        self.image_metadata.push(ImageMetadata {
+        page.image_metadata[glyph_index as usize] = ImageMetadata {
             atlas_size: pixel_size,
             glyph_index: glyph_index,
+            outline_glyph_index: outline_glyph_index,
             glyph_id: glyph_id,
+            point_size: fixed_point_size,
+            last_used: self.current_frame,
+        };
+
+        self.glyph_cache.insert((glyph_id, fixed_point_size), (page_index, glyph_index));
+
+        Ok((page_index, glyph_index))
+    }
+
+    pub fn create_atlas(&mut self, outline_builder: &OutlineBuilder) -> Result<Vec<Atlas>, ()> {
+        self.pages.iter_mut().map(|page| page.create_atlas(outline_builder)).collect()
+    }
+
+    /// Looks up the `(page_index, glyph_index)` of the image packed for `glyph_id` at
+    /// `point_size`.
+    ///
+    /// Resolving against the `(glyph_id, point_size)` pair (rather than `glyph_id` alone) is
+    /// necessary because the same glyph may be packed at several point sizes.
+    #[inline]
+    pub fn glyph_index_for(&self, glyph_id: u16, point_size: f32) -> Option<(u32, u32)> {
+        let fixed_point_size = (point_size * 65536.0) as u32;
+        self.glyph_cache.get(&(glyph_id, fixed_point_size)).cloned()
+    }
+
+    #[inline]
+    pub fn atlas_rect(&self, page_index: u32, glyph_index: u32) -> Rect<u32> {
+        self.pages[page_index as usize].atlas_rect(glyph_index)
+    }
+}
+
+/// The packing state for a single atlas page. `AtlasBuilder` starts a new one of these whenever
+/// a glyph no longer fits on the current page's `RectPacker`, even after evicting stale glyphs.
+struct AtlasPageBuilder {
+    rect_packer: RectPacker,
+    image_descriptors: Vec<ImageDescriptor>,
+    image_metadata: Vec<ImageMetadata>,
+    /// Slots freed up by `evict_unused` that haven't been reused by `alloc_slot` yet. A
+    /// `HashSet` so membership checks in `evict_unused` and `create_atlas` stay O(1) even on
+    /// long-lived atlases with many evict/reuse cycles.
+    free_slots: HashSet<u32>,
+
+    /// The `UNIFORM_BUFFER` backing `image_descriptors` on the GPU. Allocated once and resized
+    /// only when `image_descriptors` outgrows `images_capacity`, rather than being recreated on
+    /// every `create_atlas` call.
+    images: GLuint,
+    images_capacity: usize,
+    /// Non-null when `images` was allocated with `glBufferStorage` and
+    /// `MAP_PERSISTENT_BIT`/`MAP_COHERENT_BIT`, in which case writes can go straight through
+    /// this pointer with no further mapping calls.
+    persistent_ptr: Option<*mut c_void>,
+    /// The `[lo, hi)` range of `image_descriptors` slots written since the last upload, or
+    /// `None` if nothing is dirty. Only this range is re-streamed to the GPU.
+    dirty_range: Option<(u32, u32)>,
+}
+
+impl AtlasPageBuilder {
+    #[inline]
+    fn new(available_width: u32, shelf_height: u32) -> AtlasPageBuilder {
+        AtlasPageBuilder {
+            rect_packer: RectPacker::new(available_width, shelf_height),
+            image_descriptors: vec![],
+            image_metadata: vec![],
+            free_slots: HashSet::new(),
+            images: 0,
+            images_capacity: 0,
+            persistent_ptr: None,
+            dirty_range: None,
+        }
+    }
+
+    /// Returns the `glyph_index` of a slot ready to be filled in, reusing one freed by
+    /// `evict_unused` if one is available instead of growing the arrays.
+    #[inline]
+    fn alloc_slot(&mut self) -> u32 {
+        if let Some(&glyph_index) = self.free_slots.iter().next() {
+            self.free_slots.remove(&glyph_index);
+            return glyph_index
+        }
+
+        let glyph_index = self.image_descriptors.len() as u32;
+        self.image_descriptors.push(ImageDescriptor::default());
+        self.image_metadata.push(ImageMetadata::default());
+        glyph_index
+    }
+
+    /// Writes `descriptor` into slot `glyph_index` and marks it dirty so the next
+    /// `create_atlas` streams it up to the GPU.
+    #[inline]
+    fn set_descriptor(&mut self, glyph_index: u32, descriptor: ImageDescriptor) {
+        self.image_descriptors[glyph_index as usize] = descriptor;
+        self.dirty_range = Some(match self.dirty_range {
+            Some((lo, hi)) => (lo.min(glyph_index), hi.max(glyph_index + 1)),
+            None => (glyph_index, glyph_index + 1),
         });
+    }
+
+    #[inline]
+    fn touch(&mut self, glyph_index: u32, current_frame: u32) {
+        self.image_metadata[glyph_index as usize].last_used = current_frame
+    }
+
+    /// Frees the rectangles of glyphs not touched since `current_frame - eviction_threshold`,
+    /// removing their entries from `glyph_cache` and returning the rectangles to
+    /// `rect_packer`'s free list so later packs can reuse the space.
+    fn evict_unused(&mut self,
+                     current_frame: u32,
+                     eviction_threshold: u32,
+                     glyph_cache: &mut HashMap<(u16, u32), (u32, u32)>) {
+        for glyph_index in 0..self.image_metadata.len() as u32 {
+            if self.free_slots.contains(&glyph_index) {
+                continue
+            }
+
+            let metadata = self.image_metadata[glyph_index as usize];
+            if current_frame.saturating_sub(metadata.last_used) <= eviction_threshold {
+                continue
+            }
 
-        Ok(())
+            self.rect_packer.free(&self.atlas_rect(glyph_index));
+            glyph_cache.remove(&(metadata.glyph_id, metadata.point_size));
+            self.free_slots.insert(glyph_index);
+        }
     }
 
-    pub fn create_atlas(&mut self, outline_builder: &OutlineBuilder) -> Result<Atlas, ()> {
-        self.image_metadata.sort_by(|a, b| a.glyph_index.cmp(&b.glyph_index));
+    #[inline]
+    fn atlas_rect(&self, glyph_index: u32) -> Rect<u32> {
+        let descriptor = &self.image_descriptors[glyph_index as usize];
+        let metadata = &self.image_metadata[glyph_index as usize];
+        Rect::new(Point2D::new(descriptor.atlas_x, descriptor.atlas_y), metadata.atlas_size)
+    }
+
+    fn create_atlas(&mut self, outline_builder: &OutlineBuilder) -> Result<Atlas, ()> {
+        let free_slots = &self.free_slots;
+        let mut live_metadata: Vec<_> = self.image_metadata
+                                             .iter()
+                                             .enumerate()
+                                             .filter(|&(glyph_index, _)| {
+                                                 !free_slots.contains(&(glyph_index as u32))
+                                             })
+                                             .map(|(_, image_metadata)| *image_metadata)
+                                             .collect();
+        live_metadata.sort_by(|a, b| a.outline_glyph_index.cmp(&b.outline_glyph_index));
 
         let (mut current_range, mut counts, mut start_indices) = (None, vec![], vec![]);
-        for image_metadata in &self.image_metadata {
-            let glyph_index = image_metadata.glyph_index;
+        for image_metadata in &live_metadata {
+            let glyph_index = image_metadata.outline_glyph_index;
 
             let first_index = outline_builder.descriptors[glyph_index as usize]
                                              .start_index as usize;
@@ -105,43 +311,124 @@ impl AtlasBuilder {
             start_indices.push(current_first);
         }
 
-        // TODO(pcwalton): Try using `glMapBuffer` here.
+        self.upload_dirty();
+
+        Ok(Atlas {
+            start_indices: start_indices,
+            counts: counts,
+            images: self.images,
+
+            shelf_height: self.rect_packer.shelf_height(),
+            shelf_columns: self.rect_packer.shelf_columns(),
+        })
+    }
+
+    /// (Re)allocates `images` so it can hold `image_descriptors`, preferring a persistently
+    /// mapped `glBufferStorage` allocation and falling back to a plain `glBufferData` one if
+    /// `GL_ARB_buffer_storage` isn't available. Reallocating implies a full reupload, so the
+    /// whole buffer is marked dirty afterward.
+    fn ensure_buffer(&mut self) {
+        if self.images != 0 && self.image_descriptors.len() <= self.images_capacity {
+            return
+        }
+
         unsafe {
+            if self.images != 0 {
+                gl::BindBuffer(gl::UNIFORM_BUFFER, self.images);
+                if self.persistent_ptr.take().is_some() {
+                    gl::UnmapBuffer(gl::UNIFORM_BUFFER);
+                }
+                gl::DeleteBuffers(1, &self.images);
+            }
+
+            let capacity = self.image_descriptors.len().max(1).next_power_of_two();
+            let length = (capacity * mem::size_of::<ImageDescriptor>()) as GLsizeiptr;
+
             let mut images = 0;
             gl::GenBuffers(1, &mut images);
-
-            let length = self.image_descriptors.len() * mem::size_of::<ImageDescriptor>();
-            let ptr = self.image_descriptors.as_ptr() as *const ImageDescriptor as *const c_void;
             gl::BindBuffer(gl::UNIFORM_BUFFER, images);
-            gl::BufferData(gl::UNIFORM_BUFFER, length as GLsizeiptr, ptr, gl::DYNAMIC_DRAW);
 
-            Ok(Atlas {
-                start_indices: start_indices,
-                counts: counts,
-                images: images,
+            if gl::BufferStorage::is_loaded() {
+                let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+                gl::BufferStorage(gl::UNIFORM_BUFFER, length, ptr::null(), flags);
+                let mapped = gl::MapBufferRange(gl::UNIFORM_BUFFER, 0, length, flags);
+                self.persistent_ptr = if mapped.is_null() { None } else { Some(mapped) };
+            } else {
+                gl::BufferData(gl::UNIFORM_BUFFER, length, ptr::null(), gl::DYNAMIC_DRAW);
+                self.persistent_ptr = None;
+            }
 
-                shelf_height: self.rect_packer.shelf_height(),
-                shelf_columns: self.rect_packer.shelf_columns(),
-            })
+            self.images = images;
+            self.images_capacity = capacity;
+        }
+
+        if !self.image_descriptors.is_empty() {
+            self.dirty_range = Some((0, self.image_descriptors.len() as u32));
         }
     }
 
-    #[inline]
-    pub fn glyph_index_for(&self, glyph_id: u16) -> Option<u32> {
-        match self.image_metadata.binary_search_by(|metadata| metadata.glyph_id.cmp(&glyph_id)) {
-            Ok(glyph_index) => Some(self.image_metadata[glyph_index].glyph_index),
-            Err(_) => None,
+    /// Streams the `[lo, hi)` range of `image_descriptors` marked dirty by `set_descriptor` up
+    /// to `images`, via the persistent mapping if one exists, or a one-off `glMapBufferRange`
+    /// with an explicit flush otherwise. Copies nothing if nothing is dirty.
+    fn upload_dirty(&mut self) {
+        self.ensure_buffer();
+
+        let (lo, hi) = match self.dirty_range.take() {
+            Some(range) => range,
+            None => return,
+        };
+
+        let descriptor_size = mem::size_of::<ImageDescriptor>();
+        let offset = lo as usize * descriptor_size;
+        let length = (hi - lo) as usize * descriptor_size;
+
+        unsafe {
+            let src = self.image_descriptors.as_ptr().offset(lo as isize) as *const u8;
+
+            gl::BindBuffer(gl::UNIFORM_BUFFER, self.images);
+            match self.persistent_ptr {
+                Some(mapped) => {
+                    let dst = (mapped as *mut u8).offset(offset as isize);
+                    ptr::copy_nonoverlapping(src, dst, length);
+                }
+                None => {
+                    let dst = gl::MapBufferRange(gl::UNIFORM_BUFFER,
+                                                  offset as GLintptr,
+                                                  length as GLsizeiptr,
+                                                  gl::MAP_WRITE_BIT | gl::MAP_FLUSH_EXPLICIT_BIT);
+                    ptr::copy_nonoverlapping(src, dst as *mut u8, length);
+                    gl::FlushMappedBufferRange(gl::UNIFORM_BUFFER, 0, length as GLsizeiptr);
+                    gl::UnmapBuffer(gl::UNIFORM_BUFFER);
+                }
+            }
         }
     }
+}
 
-    #[inline]
-    pub fn atlas_rect(&self, glyph_index: u32) -> Rect<u32> {
-        let descriptor = &self.image_descriptors[glyph_index as usize];
-        let metadata = &self.image_metadata[glyph_index as usize];
-        Rect::new(Point2D::new(descriptor.atlas_x, descriptor.atlas_y), metadata.atlas_size)
+impl Drop for AtlasPageBuilder {
+    fn drop(&mut self) {
+        if self.images == 0 {
+            return
+        }
+
+        unsafe {
+            if self.persistent_ptr.take().is_some() {
+                gl::BindBuffer(gl::UNIFORM_BUFFER, self.images);
+                gl::UnmapBuffer(gl::UNIFORM_BUFFER);
+            }
+            gl::DeleteBuffers(1, &self.images);
+        }
     }
 }
 
+/// A single GPU-resident page of a (possibly multi-page) atlas. Render the pages returned by
+/// `AtlasBuilder::create_atlas` in sequence, binding each one's `images()` buffer before calling
+/// `draw()`.
+///
+/// `images` is owned by the `AtlasPageBuilder` the snapshot came from, not by `Atlas` itself, so
+/// it stays alive (and keeps its persistent mapping) across repeated `create_atlas` calls until
+/// the page outgrows it. At that point `create_atlas` deletes it and allocates a larger buffer,
+/// and an `Atlas` taken before then still refers to the deleted buffer.
 pub struct Atlas {
     start_indices: Vec<usize>,
     counts: Vec<GLsizei>,
@@ -151,14 +438,6 @@ pub struct Atlas {
     pub shelf_columns: u32,
 }
 
-impl Drop for Atlas {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteBuffers(1, &mut self.images);
-        }
-    }
-}
-
 impl Atlas {
     pub unsafe fn draw(&self, primitive: GLenum) {
         debug_assert!(self.counts.len() == self.start_indices.len());
@@ -186,9 +465,100 @@ pub struct ImageDescriptor {
 }
 
 /// Information about each image that we keep around ourselves.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Default, Debug)]
 pub struct ImageMetadata {
     atlas_size: Size2D<u32>,
+    /// The slot this image occupies in the page's `image_descriptors`/`image_metadata` arrays.
+    /// This is *not* the glyph's index into `outline_builder.descriptors`—see
+    /// `outline_glyph_index`—since paging and slot reuse mean the two can diverge.
     glyph_index: u32,
+    /// The glyph's index into `outline_builder.descriptors`, i.e. the `outline_glyph_index`
+    /// argument `pack_glyph` was called with. `create_atlas` uses this (not `glyph_index`) to
+    /// look up the glyph's outline range.
+    outline_glyph_index: u32,
     glyph_id: u16,
+    /// The point size this glyph was packed at, encoded the same way as
+    /// `ImageDescriptor::point_size`. Together with `glyph_id` this forms the cache key in
+    /// `AtlasBuilder::glyph_cache`.
+    point_size: u32,
+    /// The frame this glyph was last packed or `touch`-ed on, used by `evict_unused` to find
+    /// stale entries.
+    last_used: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyph_index_for_dedups_by_glyph_id_and_point_size() {
+        let mut builder = AtlasBuilder::new(256, 32, 4);
+        let fixed_point_size = (12.0f32 * 65536.0) as u32;
+
+        builder.pages[0].image_descriptors.push(ImageDescriptor::default());
+        builder.pages[0].image_metadata.push(ImageMetadata {
+            atlas_size: Size2D::new(10, 10),
+            glyph_index: 0,
+            outline_glyph_index: 0,
+            glyph_id: 42,
+            point_size: fixed_point_size,
+            last_used: 0,
+        });
+        builder.glyph_cache.insert((42, fixed_point_size), (0, 0));
+
+        // Same `(glyph_id, point_size)` pair resolves to the cached slot...
+        assert_eq!(builder.glyph_index_for(42, 12.0), Some((0, 0)));
+        // ...but a different point size is a cache miss even though the `glyph_id` alone
+        // matches, and so is a different glyph ID.
+        assert_eq!(builder.glyph_index_for(42, 13.0), None);
+        assert_eq!(builder.glyph_index_for(43, 12.0), None);
+    }
+
+    #[test]
+    fn evict_unused_reclaims_only_glyphs_past_the_threshold() {
+        let mut builder = AtlasBuilder::new(256, 32, 4);
+        let fixed_point_size = (12.0f32 * 65536.0) as u32;
+
+        // Slot 0: last touched on frame 1, stale by frame 10 with a threshold of 4.
+        // Slot 1: last touched on frame 8, still fresh.
+        for (glyph_index, (glyph_id, last_used)) in [(42u16, 1u32), (43u16, 8u32)].iter()
+                                                                                  .enumerate() {
+            let glyph_index = glyph_index as u32;
+            builder.pages[0].image_descriptors.push(ImageDescriptor::default());
+            builder.pages[0].image_metadata.push(ImageMetadata {
+                atlas_size: Size2D::new(4, 4),
+                glyph_index: glyph_index,
+                outline_glyph_index: glyph_index,
+                glyph_id: *glyph_id,
+                point_size: fixed_point_size,
+                last_used: *last_used,
+            });
+            builder.glyph_cache.insert((*glyph_id, fixed_point_size), (0, glyph_index));
+        }
+
+        builder.current_frame = 10;
+        builder.evict_unused();
+
+        assert!(builder.pages[0].free_slots.contains(&0));
+        assert!(!builder.pages[0].free_slots.contains(&1));
+        assert_eq!(builder.glyph_cache.get(&(42, fixed_point_size)), None);
+        assert_eq!(builder.glyph_cache.get(&(43, fixed_point_size)), Some(&(0, 1)));
+    }
+
+    #[test]
+    fn set_descriptor_merges_dirty_range() {
+        let mut page = AtlasPageBuilder::new(256, 32);
+        page.image_descriptors = vec![ImageDescriptor::default(); 4];
+
+        page.set_descriptor(2, ImageDescriptor::default());
+        assert_eq!(page.dirty_range, Some((2, 3)));
+
+        // A later write outside the current range should widen it rather than replace it.
+        page.set_descriptor(0, ImageDescriptor::default());
+        assert_eq!(page.dirty_range, Some((0, 3)));
+
+        // A write already inside the range shouldn't shrink it.
+        page.set_descriptor(1, ImageDescriptor::default());
+        assert_eq!(page.dirty_range, Some((0, 3)));
+    }
 }
@@ -0,0 +1,168 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use euclid::{Point2D, Rect, Size2D};
+
+/// A fixed-height shelf packer: the atlas is divided into horizontal shelves of `shelf_height`
+/// pixels each, and rectangles are placed left-to-right along whichever shelf has room. Used by
+/// `AtlasBuilder` to lay out glyph images.
+pub struct RectPacker {
+    available_width: u32,
+    available_height: u32,
+    shelf_height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl RectPacker {
+    /// FIXME(pcwalton): Assumes a square atlas (`available_height == available_width`). Take an
+    /// explicit height if that stops being a safe assumption.
+    #[inline]
+    pub fn new(available_width: u32, shelf_height: u32) -> RectPacker {
+        RectPacker {
+            available_width: available_width,
+            available_height: available_width,
+            shelf_height: shelf_height,
+            shelves: vec![],
+        }
+    }
+
+    /// Packs `size` into the atlas, returning its origin, or `Err(())` if no shelf has room and
+    /// there's no vertical space left to start a new one.
+    pub fn pack(&mut self, size: &Size2D<u32>) -> Result<Point2D<u32>, ()> {
+        if size.width > self.available_width || size.height > self.shelf_height {
+            return Err(())
+        }
+
+        for shelf in &mut self.shelves {
+            if let Some(x) = shelf.alloc_from_free_spans(size.width) {
+                return Ok(Point2D::new(x, shelf.y))
+            }
+        }
+
+        for shelf in &mut self.shelves {
+            if shelf.next_x + size.width <= self.available_width {
+                let x = shelf.next_x;
+                shelf.next_x += size.width;
+                return Ok(Point2D::new(x, shelf.y))
+            }
+        }
+
+        let shelf_y = self.shelves.len() as u32 * self.shelf_height;
+        if shelf_y + self.shelf_height > self.available_height {
+            return Err(())
+        }
+
+        self.shelves.push(Shelf { y: shelf_y, next_x: size.width, free_spans: vec![] });
+        Ok(Point2D::new(0, shelf_y))
+    }
+
+    /// Returns a previously packed rectangle's space to its shelf's free list, coalescing it
+    /// with any adjacent free spans so later `pack` calls can reuse the reclaimed space.
+    pub fn free(&mut self, rect: &Rect<u32>) {
+        let shelf_index = (rect.origin.y / self.shelf_height) as usize;
+        if let Some(shelf) = self.shelves.get_mut(shelf_index) {
+            shelf.free(rect.origin.x, rect.size.width);
+        }
+    }
+
+    #[inline]
+    pub fn shelf_height(&self) -> u32 {
+        self.shelf_height
+    }
+
+    #[inline]
+    pub fn shelf_columns(&self) -> u32 {
+        self.available_width / self.shelf_height
+    }
+}
+
+struct Shelf {
+    y: u32,
+    /// The next unallocated x position; everything to the left is either live or in
+    /// `free_spans`.
+    next_x: u32,
+    /// Freed `(x, width)` spans within `[0, next_x)`, kept sorted and coalesced by `free`.
+    free_spans: Vec<(u32, u32)>,
+}
+
+impl Shelf {
+    fn alloc_from_free_spans(&mut self, width: u32) -> Option<u32> {
+        let span_index = self.free_spans.iter().position(|&(_, span_width)| span_width >= width);
+        let span_index = match span_index {
+            Some(span_index) => span_index,
+            None => return None,
+        };
+
+        let (x, span_width) = self.free_spans[span_index];
+        if span_width == width {
+            self.free_spans.remove(span_index);
+        } else {
+            self.free_spans[span_index] = (x + width, span_width - width);
+        }
+        Some(x)
+    }
+
+    fn free(&mut self, x: u32, width: u32) {
+        self.free_spans.push((x, width));
+        self.free_spans.sort_by_key(|&(x, _)| x);
+
+        // Coalesce adjacent/overlapping spans so fragmentation doesn't accumulate across many
+        // evict/pack cycles.
+        let mut coalesced: Vec<(u32, u32)> = Vec::with_capacity(self.free_spans.len());
+        for &(x, width) in &self.free_spans {
+            let merged = match coalesced.last_mut() {
+                Some(&mut (last_x, ref mut last_width)) if x <= last_x + *last_width => {
+                    *last_width = (*last_width).max(x + width - last_x);
+                    true
+                }
+                _ => false,
+            };
+            if !merged {
+                coalesced.push((x, width));
+            }
+        }
+        self.free_spans = coalesced;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_exhausts_once_shelves_fill_the_atlas() {
+        // A 16x16 atlas with 8px shelves: exactly two shelf rows fit.
+        let mut packer = RectPacker::new(16, 8);
+
+        assert_eq!(packer.pack(&Size2D::new(16, 8)), Ok(Point2D::new(0, 0)));
+        assert_eq!(packer.pack(&Size2D::new(16, 8)), Ok(Point2D::new(0, 8)));
+        // No room left for a third shelf, and the first two are already full width.
+        assert_eq!(packer.pack(&Size2D::new(1, 1)), Err(()));
+    }
+
+    #[test]
+    fn free_reclaims_and_coalesces_adjacent_spans() {
+        // A 16x16 atlas with a single 16px shelf, so there's no room to start a second one.
+        let mut packer = RectPacker::new(16, 16);
+
+        let a = packer.pack(&Size2D::new(4, 8)).unwrap();
+        let b = packer.pack(&Size2D::new(4, 8)).unwrap();
+        packer.pack(&Size2D::new(4, 8)).unwrap();
+        // 12 of the shelf's 16 columns are used and nothing is freed yet, so a 5-wide rect
+        // doesn't fit.
+        assert_eq!(packer.pack(&Size2D::new(5, 8)), Err(()));
+
+        // Freeing two adjacent rectangles should coalesce into one 8-wide span, wide enough for
+        // a rect that wouldn't fit in either piece alone.
+        packer.free(&Rect::new(a, Size2D::new(4, 8)));
+        packer.free(&Rect::new(b, Size2D::new(4, 8)));
+        assert_eq!(packer.pack(&Size2D::new(8, 8)), Ok(a));
+    }
+}